@@ -10,11 +10,15 @@ use crate::{
     device::Device,
     devres::Devres,
     error::{Error, Result},
-    irq::{IrqFlags, IrqHandler, IrqRegistration, ThreadContext, ThreadedIrqHandler},
+    irq::{
+        impl_irq_line_ops,
+        irqchip::{IrqChip, IrqDomain},
+        IrqFlags, IrqHandler, IrqRegistration, ThreadContext, ThreadedIrqHandler,
+    },
     prelude::*,
     str::CStr,
 };
-use core::pin::Pin;
+use core::{marker::PhantomData, pin::Pin};
 
 /// Type alias for a device-managed IRQ registration.
 type DevresIrqRegistration<T> =
@@ -24,6 +28,13 @@ type DevresIrqRegistration<T> =
 type DevresThreadedIrqRegistration<T> =
     Devres<Pin<Box<ThreadedIrqRegistration<T>, crate::alloc::allocator::Kmalloc>>>;
 
+/// Type alias for a device-managed per-CPU IRQ registration.
+type DevresPercpuIrqRegistration<T> =
+    Devres<Pin<Box<PercpuIrqRegistration<T>, crate::alloc::allocator::Kmalloc>>>;
+
+/// Type alias for a device-managed interrupt domain.
+type DevresIrqDomain<C> = Devres<Pin<Box<IrqDomain<C>, crate::alloc::allocator::Kmalloc>>>;
+
 /// Device extension for IRQ management.
 impl Device {
     /// Request a device-managed IRQ.
@@ -42,7 +53,7 @@ impl Device {
     ) -> Result<DevresIrqRegistration<T>> {
         // SAFETY: Caller guarantees IRQ is valid.
         let registration = Box::pin_init(
-            unsafe { IrqRegistration::<T>::request(irq, handler_data, flags, name) },
+            unsafe { IrqRegistration::<T>::try_request(irq, handler_data, flags, name) },
             crate::alloc::flags::GFP_KERNEL,
         )?;
 
@@ -65,12 +76,63 @@ impl Device {
     ) -> Result<DevresThreadedIrqRegistration<T>> {
         // SAFETY: Caller guarantees IRQ is valid.
         let registration = Box::pin_init(
-            unsafe { ThreadedIrqRegistration::<T>::request(irq, handler_data, flags, name) },
+            unsafe { ThreadedIrqRegistration::<T>::try_request(irq, handler_data, flags, name) },
+            crate::alloc::flags::GFP_KERNEL,
+        )?;
+
+        Devres::new(self, registration, crate::alloc::flags::GFP_KERNEL)
+    }
+
+    /// Request a device-managed per-CPU IRQ.
+    ///
+    /// Per-CPU interrupts (timers, IPIs, PMU counters on GIC-style controllers)
+    /// are enabled and disabled per CPU rather than globally. `percpu_dev_id`
+    /// must be a `__percpu` pointer to the per-CPU handler data, valid until the
+    /// device is unbound; the kernel hands each CPU's element to the handler.
+    ///
+    /// The IRQ is freed with `free_percpu_irq` when the device is unbound.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `irq` is a valid per-CPU IRQ number for this
+    /// device and that `percpu_dev_id` is a valid `__percpu` allocation of
+    /// `T::Data` outliving the registration.
+    pub unsafe fn request_percpu_irq<T: IrqHandler>(
+        &self,
+        irq: u32,
+        percpu_dev_id: *mut core::ffi::c_void,
+        name: &CStr,
+    ) -> Result<DevresPercpuIrqRegistration<T>> {
+        // SAFETY: Caller guarantees IRQ number and per-CPU cookie are valid.
+        let registration = Box::pin_init(
+            unsafe { PercpuIrqRegistration::<T>::try_request(irq, percpu_dev_id, name) },
             crate::alloc::flags::GFP_KERNEL,
         )?;
 
         Devres::new(self, registration, crate::alloc::flags::GFP_KERNEL)
     }
+
+    /// Create a device-managed linear interrupt domain.
+    ///
+    /// This lets a Rust driver *implement* an interrupt controller: `chip`
+    /// supplies the per-line [`IrqChip`] operations and the returned domain maps
+    /// hardware IRQ numbers (`hwirq`) onto Linux `virq`s. The domain is removed
+    /// automatically when the device is unbound.
+    ///
+    /// # Safety
+    ///
+    /// `fwnode` must be a valid firmware node for this controller, or null for
+    /// an orphan domain.
+    pub unsafe fn create_irq_domain_linear<C: IrqChip>(
+        &self,
+        fwnode: *mut bindings::fwnode_handle,
+        size: u32,
+        chip: C,
+    ) -> Result<DevresIrqDomain<C>> {
+        // SAFETY: Caller guarantees `fwnode` is valid.
+        let domain = unsafe { IrqDomain::<C>::add_linear(fwnode, size, chip) }?;
+        Devres::new(self, domain, crate::alloc::flags::GFP_KERNEL)
+    }
 }
 
 /// Registration for threaded interrupt handlers.
@@ -83,6 +145,8 @@ pub struct ThreadedIrqRegistration<T: ThreadedIrqHandler> {
     #[pin]
     handler_data: T::Data,
     dev_id: *mut core::ffi::c_void,
+    /// Net count of wakeup enables, to enforce balanced wake calls.
+    wake_depth: core::sync::atomic::AtomicU64,
 }
 
 // SAFETY: ThreadedIrqRegistration can be transferred between threads.
@@ -94,8 +158,13 @@ unsafe impl<T: ThreadedIrqHandler> Sync for ThreadedIrqRegistration<T> {}
 #[pinned_drop]
 impl<T: ThreadedIrqHandler> PinnedDrop for ThreadedIrqRegistration<T> {
     fn drop(self: Pin<&mut Self>) {
-        // SAFETY: We're in drop, so no more users of this IRQ.
-        // The IRQ was successfully registered, so it's safe to free it.
+        // A null `dev_id` means `request_threaded_irq` failed (or never ran), so
+        // there is nothing to free.
+        if self.dev_id.is_null() {
+            return;
+        }
+        // SAFETY: We're in drop, so no more users of this IRQ, and a non-null
+        // `dev_id` means the IRQ was successfully registered.
         unsafe {
             bindings::free_irq(self.irq, self.dev_id);
         }
@@ -105,15 +174,21 @@ impl<T: ThreadedIrqHandler> PinnedDrop for ThreadedIrqRegistration<T> {
 impl<T: ThreadedIrqHandler> ThreadedIrqRegistration<T> {
     /// Request a threaded IRQ with the given handler.
     ///
+    /// Surfaces a `request_threaded_irq` failure (e.g. `-EBUSY` on a shared
+    /// line, or `-ENOSYS`) as an `Err` out of the pin-init chain instead of
+    /// panicking, so a probe routine can propagate the errno. On failure
+    /// `dev_id` is left null, so the partially constructed registration drops
+    /// without ever calling `free_irq` on an IRQ that was never registered.
+    ///
     /// # Safety
     ///
     /// The caller must ensure that `irq` is a valid IRQ number for the platform.
-    pub unsafe fn request(
+    pub unsafe fn try_request(
         irq: u32,
         handler_data: T::Data,
         flags: IrqFlags,
         name: &CStr,
-    ) -> impl PinInit<Self> {
+    ) -> impl PinInit<Self, Error> {
         let flags = flags.raw() as usize;
         let name = name.as_char_ptr();
 
@@ -121,6 +196,7 @@ impl<T: ThreadedIrqHandler> ThreadedIrqRegistration<T> {
             irq,
             handler_data: handler_data,
             dev_id: core::ptr::null_mut(),
+            wake_depth: core::sync::atomic::AtomicU64::new(0),
         })
         .pin_chain(move |slot| {
             // SAFETY: We're initializing dev_id to point to ourself.
@@ -141,11 +217,8 @@ impl<T: ThreadedIrqHandler> ThreadedIrqRegistration<T> {
             };
 
             if ret < 0 {
-                panic!(
-                    "Failed to request threaded IRQ {}: {:?}",
-                    irq,
-                    Error::from_errno(ret)
-                );
+                // Leave `dev_id` null so the drop-time `free_irq` is skipped.
+                return Err(Error::from_errno(ret));
             }
 
             // SAFETY: Pointer is valid for lifetime of registration.
@@ -180,12 +253,30 @@ impl<T: ThreadedIrqHandler> ThreadedIrqRegistration<T> {
         }
     }
 
+    /// Wait only for in-flight *hard* IRQ handlers to finish.
+    ///
+    /// Wraps `synchronize_hardirq`. Unlike [`synchronize`], it does not wait for
+    /// the threaded handler, so it is appropriate when only the hard half must
+    /// be quiesced.
+    ///
+    /// Must not be called from IRQ or atomic context.
+    ///
+    /// [`synchronize`]: Self::synchronize
+    pub fn synchronize_hardirq(&self) {
+        // SAFETY: IRQ number is valid because we successfully registered it.
+        unsafe {
+            bindings::synchronize_hardirq(self.irq);
+        }
+    }
+
     /// Get the IRQ number.
     pub fn irq(&self) -> u32 {
         self.irq
     }
 }
 
+impl_irq_line_ops!(ThreadedIrqRegistration<ThreadedIrqHandler>);
+
 /// Adapter function for hard IRQ handler in threaded IRQs.
 ///
 /// # Safety
@@ -204,7 +295,7 @@ unsafe extern "C" fn irq_handler_callback<T: ThreadedIrqHandler>(
     // SAFETY: We're in IRQ context.
     let ctx = unsafe { crate::irq::IrqContext::new() };
 
-    T::handle_irq(&reg.handler_data, &ctx) as _
+    T::handle_irq(&reg.handler_data, &ctx).into_raw()
 }
 
 /// Adapter function for threaded IRQ handler.
@@ -223,5 +314,138 @@ unsafe extern "C" fn irq_thread_callback<T: ThreadedIrqHandler>(
     let reg = unsafe { &*(dev_id as *const ThreadedIrqRegistration<T>) };
 
     let ctx = ThreadContext::new();
-    T::handle_thread(&reg.handler_data, &ctx) as _
+    T::handle_thread(&reg.handler_data, &ctx).into_raw()
+}
+
+/// Registration for per-CPU interrupt handlers.
+///
+/// Per-CPU IRQs are enabled and disabled on each CPU independently rather than
+/// globally. The handler data lives in a `__percpu` allocation; the kernel
+/// passes the local CPU's element to the handler on each firing.
+#[pin_data(PinnedDrop)]
+pub struct PercpuIrqRegistration<T: IrqHandler> {
+    irq: u32,
+    /// `__percpu` cookie passed to `request_percpu_irq`/`free_percpu_irq`.
+    percpu_dev_id: *mut core::ffi::c_void,
+    _p: PhantomData<T>,
+}
+
+// SAFETY: PercpuIrqRegistration can be transferred between threads.
+unsafe impl<T: IrqHandler> Send for PercpuIrqRegistration<T> {}
+
+// SAFETY: PercpuIrqRegistration can be shared between threads.
+unsafe impl<T: IrqHandler> Sync for PercpuIrqRegistration<T> {}
+
+#[pinned_drop]
+impl<T: IrqHandler> PinnedDrop for PercpuIrqRegistration<T> {
+    fn drop(self: Pin<&mut Self>) {
+        // A null cookie means `request_percpu_irq` failed, so there is nothing
+        // to free.
+        if self.percpu_dev_id.is_null() {
+            return;
+        }
+        // SAFETY: We're in drop, so no more users of this IRQ, and the per-CPU
+        // cookie matches the one passed at registration time.
+        unsafe {
+            bindings::free_percpu_irq(self.irq, self.percpu_dev_id);
+        }
+    }
+}
+
+impl<T: IrqHandler> PercpuIrqRegistration<T> {
+    /// Request a per-CPU IRQ with the given handler.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `irq` is a valid per-CPU IRQ number and that
+    /// `percpu_dev_id` is a valid `__percpu` allocation of `T::Data`.
+    pub unsafe fn try_request(
+        irq: u32,
+        percpu_dev_id: *mut core::ffi::c_void,
+        name: &CStr,
+    ) -> impl PinInit<Self, Error> {
+        let name = name.as_char_ptr();
+
+        pin_init!(Self {
+            irq,
+            percpu_dev_id: core::ptr::null_mut(),
+            _p: PhantomData,
+        })
+        .pin_chain(move |slot| {
+            // SAFETY: `slot` is a valid pinned reference to this registration.
+            let slot_ptr = unsafe { slot.get_unchecked_mut() } as *mut Self;
+
+            // SAFETY: The callback is a valid function pointer, and the caller
+            // guarantees `irq` and the per-CPU cookie are valid.
+            let ret = unsafe {
+                bindings::request_percpu_irq(
+                    irq,
+                    Some(percpu_handler_callback::<T>),
+                    name,
+                    percpu_dev_id,
+                )
+            };
+
+            if ret < 0 {
+                // Leave the cookie null so the drop-time free is skipped.
+                return Err(Error::from_errno(ret));
+            }
+
+            // SAFETY: Pointer is valid for lifetime of registration.
+            unsafe {
+                (*slot_ptr).percpu_dev_id = percpu_dev_id;
+            }
+            Ok(())
+        })
+    }
+
+    /// Enable delivery of this interrupt on the calling CPU.
+    ///
+    /// Wraps `enable_percpu_irq`, which only ever acts on the CPU it runs on, so
+    /// the caller must already be pinned to the target CPU (e.g. from the
+    /// per-CPU startup path). There is deliberately no CPU selector: the kernel
+    /// helper cannot enable the line on a remote CPU.
+    pub fn enable_percpu(&self) {
+        // SAFETY: IRQ number is valid because we successfully registered it;
+        // the trigger type is inherited from the controller.
+        unsafe {
+            bindings::enable_percpu_irq(self.irq, bindings::IRQ_TYPE_NONE);
+        }
+    }
+
+    /// Disable delivery of this interrupt on the calling CPU.
+    ///
+    /// Wraps `disable_percpu_irq`, which acts on the calling CPU only.
+    pub fn disable_percpu(&self) {
+        // SAFETY: IRQ number is valid because we successfully registered it.
+        unsafe {
+            bindings::disable_percpu_irq(self.irq);
+        }
+    }
+
+    /// Get the IRQ number.
+    pub fn irq(&self) -> u32 {
+        self.irq
+    }
+}
+
+/// Adapter function for per-CPU IRQ handlers.
+///
+/// # Safety
+///
+/// - Must only be called by the kernel's IRQ subsystem
+/// - `dev_id` must be the calling CPU's element of the `__percpu` handler data
+/// - Must be called in hard IRQ context
+unsafe extern "C" fn percpu_handler_callback<T: IrqHandler>(
+    _irq: core::ffi::c_int,
+    dev_id: *mut core::ffi::c_void,
+) -> core::ffi::c_uint {
+    // SAFETY: For per-CPU IRQs the core passes this CPU's element of the
+    // `__percpu` handler data, which is a valid `T::Data`.
+    let data = unsafe { &*(dev_id as *const T::Data) };
+
+    // SAFETY: We're in IRQ context.
+    let ctx = unsafe { crate::irq::IrqContext::new() };
+
+    T::handle_irq(data, &ctx).into_raw()
 }
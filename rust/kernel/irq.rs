@@ -23,10 +23,21 @@
 //! }
 //! ```
 
+pub mod irqchip;
+pub mod msi;
 pub mod revocable;
 
-use crate::{bindings, error::Error, prelude::*, str::CStr};
-use core::{marker::PhantomData, pin::Pin};
+use crate::{
+    bindings,
+    error::{Error, Result},
+    prelude::*,
+    str::CStr,
+};
+use core::{
+    marker::PhantomData,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 /// IRQ return values indicating whether interrupt was handled.
 #[repr(u32)]
@@ -40,6 +51,22 @@ pub enum IrqReturn {
     WakeThread = bindings::irqreturn_IRQ_WAKE_THREAD,
 }
 
+impl IrqReturn {
+    /// Map to the raw `irqreturn_t` value the kernel expects from a handler.
+    ///
+    /// Because the enum is `#[repr(u32)]` with each variant set to the matching
+    /// `irqreturn_*` binding, this is value-identical to a plain `self as _`
+    /// cast; it only spells the mapping out explicitly so the correspondence is
+    /// checked variant-by-variant.
+    pub(crate) fn into_raw(self) -> core::ffi::c_uint {
+        match self {
+            IrqReturn::None => bindings::irqreturn_IRQ_NONE,
+            IrqReturn::Handled => bindings::irqreturn_IRQ_HANDLED,
+            IrqReturn::WakeThread => bindings::irqreturn_IRQ_WAKE_THREAD,
+        }
+    }
+}
+
 /// Marker type for hard IRQ context where sleeping is forbidden.
 ///
 /// This type is used as a parameter to interrupt handlers to ensure at
@@ -94,6 +121,19 @@ impl IrqFlags {
     /// Interrupt cannot be threaded.
     pub const NO_THREAD: Self = Self(bindings::IRQF_NO_THREAD as _);
 
+    /// Exclude the interrupt from irqbalance migration.
+    pub const NO_BALANCING: Self = Self(bindings::IRQF_NOBALANCING as _);
+
+    /// Mark the interrupt as pollable for the spurious-IRQ watchdog.
+    pub const IRQPOLL: Self = Self(bindings::IRQF_IRQPOLL as _);
+
+    /// Keep the interrupt enabled across system suspend.
+    pub const NO_SUSPEND: Self = Self(bindings::IRQF_NO_SUSPEND as _);
+
+    /// Suspend the interrupt only if no action on the shared line is a wakeup
+    /// source.
+    pub const COND_SUSPEND: Self = Self(bindings::IRQF_COND_SUSPEND as _);
+
     /// Add shared flag.
     pub const fn shared(self) -> Self {
         Self(self.0 | Self::SHARED.0)
@@ -108,6 +148,11 @@ impl IrqFlags {
     pub(crate) fn raw(self) -> core::ffi::c_ulong {
         self.0
     }
+
+    /// Returns `true` if every flag in `other` is set in `self`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
 }
 
 impl core::ops::BitOr for IrqFlags {
@@ -238,6 +283,17 @@ pub struct IrqRegistration<T: IrqHandler> {
     #[pin]
     handler_data: T::Data,
     dev_id: *mut core::ffi::c_void,
+    /// Number of times the handler returned [`IrqReturn::None`].
+    spurious: AtomicU64,
+    /// Number of times the handler returned [`IrqReturn::Handled`] or
+    /// [`IrqReturn::WakeThread`].
+    handled: AtomicU64,
+    /// Net count of wakeup enables, to enforce balanced [`enable_wake`]/
+    /// [`disable_wake`] calls.
+    ///
+    /// [`enable_wake`]: Self::enable_wake
+    /// [`disable_wake`]: Self::disable_wake
+    wake_depth: AtomicU64,
 }
 
 // SAFETY: IrqRegistration can be transferred between threads.
@@ -249,26 +305,167 @@ unsafe impl<T: IrqHandler> Sync for IrqRegistration<T> {}
 #[pinned_drop]
 impl<T: IrqHandler> PinnedDrop for IrqRegistration<T> {
     fn drop(self: Pin<&mut Self>) {
-        // SAFETY: We're in drop, so no more users of this IRQ.
-        // The IRQ was successfully registered, so it's safe to free it.
+        // A null `dev_id` means `request_irq` failed (or never ran), so there
+        // is nothing to free.
+        if self.dev_id.is_null() {
+            return;
+        }
+        // SAFETY: We're in drop, so no more users of this IRQ, and a non-null
+        // `dev_id` means the IRQ was successfully registered.
         unsafe {
             bindings::free_irq(self.irq, self.dev_id);
         }
     }
 }
 
+/// Emit the IRQ-line operations shared by the registration types.
+///
+/// Both [`IrqRegistration`] and [`ThreadedIrqRegistration`] carry an `irq`
+/// (`virq`) and a `wake_depth` counter. This macro generates the wrappers keyed
+/// off those two fields for a given registration type and handler bound, so the
+/// two types cannot drift apart.
+///
+/// [`ThreadedIrqRegistration`]: crate::device::irq::ThreadedIrqRegistration
+macro_rules! impl_irq_line_ops {
+    ($ty:ident < $bound:path >) => {
+        impl<T: $bound> $ty<T> {
+            /// Wait for any in-flight handlers to finish.
+            ///
+            /// Wraps `synchronize_irq`, which blocks until `irqd_irq_inprogress`
+            /// clears and every action handler on the line has returned,
+            /// including the threaded handler where one is registered. After it
+            /// returns, no handler is executing on any CPU.
+            ///
+            /// Must only be called from a sleepable context, never from hard-IRQ
+            /// or atomic context.
+            pub fn synchronize(&self) {
+                // SAFETY: IRQ number is valid because we successfully registered it.
+                unsafe {
+                    $crate::bindings::synchronize_irq(self.irq);
+                }
+            }
+
+            /// Steer the interrupt onto the given set of CPUs.
+            ///
+            /// Wraps `irq_set_affinity`. Returns an error if the controller
+            /// cannot set affinity for this line (`irq_can_set_affinity` is
+            /// false). Where the line has a threaded handler, the kernel moves
+            /// the associated IRQ thread to follow the hardirq affinity (see the
+            /// `irq_set_thread_affinity` path), so the kthread follows the mask
+            /// automatically.
+            pub fn set_affinity(&self, cpus: &$crate::cpumask::Cpumask) -> Result {
+                // SAFETY: IRQ number is valid and `cpus` exposes a valid mask pointer.
+                $crate::error::to_result(unsafe {
+                    $crate::bindings::irq_set_affinity(self.irq, cpus.as_raw())
+                })
+            }
+
+            /// Record an affinity *hint* for userspace (irqbalance) to honour.
+            ///
+            /// Wraps `irq_set_affinity_hint`. Unlike [`set_affinity`], this does
+            /// not itself move the interrupt.
+            ///
+            /// [`set_affinity`]: Self::set_affinity
+            pub fn set_affinity_hint(&self, cpus: &$crate::cpumask::Cpumask) -> Result {
+                // SAFETY: IRQ number is valid and `cpus` exposes a valid mask pointer.
+                $crate::error::to_result(unsafe {
+                    $crate::bindings::irq_set_affinity_hint(self.irq, cpus.as_raw())
+                })
+            }
+
+            /// Returns the affinity mask the kernel actually programmed.
+            ///
+            /// Reads `irq_data_get_effective_affinity_mask`, which may differ
+            /// from the requested set on controllers that can only target a
+            /// single CPU.
+            pub fn effective_affinity(&self) -> Option<&$crate::cpumask::Cpumask> {
+                // SAFETY: IRQ number is valid because we successfully registered it.
+                let data = unsafe { $crate::bindings::irq_get_irq_data(self.irq) };
+                if data.is_null() {
+                    return None;
+                }
+                // SAFETY: `data` is valid; the returned mask lives as long as the
+                // interrupt descriptor, i.e. at least as long as this registration.
+                let raw =
+                    unsafe { $crate::bindings::irq_data_get_effective_affinity_mask(data) };
+                // SAFETY: `raw` points at a valid `struct cpumask`.
+                Some(unsafe { $crate::cpumask::Cpumask::from_raw(raw) })
+            }
+
+            /// Arm this interrupt as a system wakeup source.
+            ///
+            /// Wraps `enable_irq_wake`. When armed, the PM core keeps the line
+            /// enabled across system suspend (`kernel/irq/pm.c`) so the device
+            /// can resume the system. The underlying enable is reference counted;
+            /// this wrapper tracks the net depth so unbalanced calls are
+            /// rejected, and any outstanding wake enable is dropped automatically
+            /// when the registration is freed. Returns an error on controllers
+            /// that do not support wakeup.
+            ///
+            /// This is independent of `disable`: a line may be wake-enabled yet
+            /// still be disabled for normal delivery while the system is running.
+            pub fn enable_wake(&self) -> Result {
+                // SAFETY: IRQ number is valid because we successfully registered it.
+                $crate::error::to_result(unsafe {
+                    $crate::bindings::enable_irq_wake(self.irq)
+                })?;
+                self.wake_depth
+                    .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+                Ok(())
+            }
+
+            /// Disarm this interrupt as a wakeup source.
+            ///
+            /// Wraps `disable_irq_wake`. Rejects an unbalanced disable (more
+            /// disables than enables). The wake reference is claimed atomically
+            /// so concurrent disables cannot both pass the balance check and
+            /// underflow the counter.
+            pub fn disable_wake(&self) -> Result {
+                use core::sync::atomic::Ordering;
+                // Atomically claim one wake reference, rejecting an unbalanced disable.
+                self.wake_depth
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |d| {
+                        d.checked_sub(1)
+                    })
+                    .map_err(|_| EINVAL)?;
+                // SAFETY: IRQ number is valid because we successfully registered it.
+                match $crate::error::to_result(unsafe {
+                    $crate::bindings::disable_irq_wake(self.irq)
+                }) {
+                    Ok(()) => Ok(()),
+                    Err(e) => {
+                        // Roll back the claimed reference if the kernel rejected it.
+                        self.wake_depth.fetch_add(1, Ordering::Relaxed);
+                        Err(e)
+                    }
+                }
+            }
+        }
+    };
+}
+pub(crate) use impl_irq_line_ops;
+
+impl_irq_line_ops!(IrqRegistration<IrqHandler>);
+
 impl<T: IrqHandler> IrqRegistration<T> {
     /// Request an IRQ with the given handler.
     ///
+    /// Unlike a panicking variant, this surfaces a `request_irq` failure (e.g.
+    /// `-EBUSY` on a contended shared line, or `-ENOMEM`) as an `Err` out of the
+    /// pin-init chain, so a probe routine can propagate the errno up the stack.
+    /// The RAII `free_irq`-on-drop behaviour is preserved for the success case;
+    /// on failure `dev_id` is left null so drop never calls `free_irq` on an IRQ
+    /// that was never registered.
+    ///
     /// # Safety
     ///
     /// The caller must ensure that `irq` is a valid IRQ number for the platform.
-    pub unsafe fn request(
+    pub unsafe fn try_request(
         irq: u32,
         handler_data: T::Data,
         flags: IrqFlags,
         name: &CStr,
-    ) -> impl PinInit<Self> {
+    ) -> impl PinInit<Self, Error> {
         let flags = flags.raw() as usize;
         let name = name.as_char_ptr();
 
@@ -276,6 +473,9 @@ impl<T: IrqHandler> IrqRegistration<T> {
             irq,
             handler_data: handler_data,
             dev_id: core::ptr::null_mut(),
+            spurious: AtomicU64::new(0),
+            handled: AtomicU64::new(0),
+            wake_depth: AtomicU64::new(0),
         })
         .pin_chain(move |slot| {
             // SAFETY: We're initializing dev_id to point to ourself.
@@ -290,15 +490,8 @@ impl<T: IrqHandler> IrqRegistration<T> {
             };
 
             if ret < 0 {
-                // We can't return an error from pin_chain in a way that works
-                // with the current pin_init macro, so we panic on failure.
-                // In practice, this should be wrapped by a higher-level API
-                // that does proper error checking.
-                panic!(
-                    "Failed to request IRQ {}: {:?}",
-                    irq,
-                    Error::from_errno(ret)
-                );
+                // Leave `dev_id` null so the drop-time `free_irq` is skipped.
+                return Err(Error::from_errno(ret));
             }
 
             // SAFETY: Pointer is valid for lifetime of registration.
@@ -333,12 +526,114 @@ impl<T: IrqHandler> IrqRegistration<T> {
         }
     }
 
+    /// Number of interrupts this handler declined ([`IrqReturn::None`]).
+    ///
+    /// A climbing count on a shared line is the signature of a misbehaving
+    /// sharer, which the kernel's spurious-IRQ watchdog (`irq/spurious.c`)
+    /// reacts to past a threshold.
+    ///
+    /// This safety layer provides accounting plus the [`IrqFlags::IRQPOLL`]
+    /// opt-in, which hands the line to that in-kernel watchdog. It deliberately
+    /// does not expose a manual re-dispatch of the other sharers: the C poll
+    /// path (`poll_spurious_irqs`/`try_one_irq` in `irq/spurious.c`) is internal
+    /// to genirq with no exported, driver-callable entry point, so the watchdog
+    /// flag is the supported mechanism.
+    pub fn spurious_count(&self) -> u64 {
+        self.spurious.load(Ordering::Relaxed)
+    }
+
+    /// Number of interrupts this handler serviced ([`IrqReturn::Handled`] or
+    /// [`IrqReturn::WakeThread`]).
+    pub fn handled_count(&self) -> u64 {
+        self.handled.load(Ordering::Relaxed)
+    }
+
+    /// Update the spurious/handled accounting for a handler return value.
+    fn account(&self, ret: IrqReturn) {
+        match ret {
+            IrqReturn::None => {
+                self.spurious.fetch_add(1, Ordering::Relaxed);
+            }
+            IrqReturn::Handled | IrqReturn::WakeThread => {
+                self.handled.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
     /// Get the IRQ number.
     pub fn irq(&self) -> u32 {
         self.irq
     }
 }
 
+impl<T: ThreadedIrqHandler> IrqRegistration<T> {
+    /// Request a threaded IRQ with the given handler.
+    ///
+    /// The hard-IRQ top half runs `T::handle_irq` and decides, via its
+    /// [`IrqReturn`], whether to wake the kthread that runs `T::handle_thread`.
+    /// This mirrors the kernel's forced-threading model, where the primary
+    /// handler returns `IRQ_WAKE_THREAD` and the sleepable bottom half runs in
+    /// a dedicated kthread.
+    ///
+    /// A device-specific primary handler (`T::handle_irq`) is always supplied,
+    /// so a non-oneshot threaded line is legal here; [`IrqFlags::ONESHOT`] is
+    /// only mandatory on the kernel's default-threaded path, where the primary
+    /// is `NULL`. This constructor never takes that path. A failure of
+    /// `request_threaded_irq` is returned as an `Err` from the initializer
+    /// rather than a panic.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `irq` is a valid IRQ number for the platform.
+    pub unsafe fn request_threaded(
+        irq: u32,
+        handler_data: T::Data,
+        flags: IrqFlags,
+        name: &CStr,
+    ) -> impl PinInit<Self, Error> {
+        let flags = flags.raw() as usize;
+        let name = name.as_char_ptr();
+
+        pin_init!(Self {
+            irq,
+            handler_data: handler_data,
+            dev_id: core::ptr::null_mut(),
+            spurious: AtomicU64::new(0),
+            handled: AtomicU64::new(0),
+            wake_depth: AtomicU64::new(0),
+        })
+        .pin_chain(move |slot| {
+            // SAFETY: We're initializing dev_id to point to ourself.
+            let slot_ptr = unsafe { slot.get_unchecked_mut() } as *mut Self;
+            let dev_id = slot_ptr as *mut core::ffi::c_void;
+
+            // SAFETY: Both callbacks are valid function pointers, and the caller
+            // guarantees irq is valid.
+            let ret = unsafe {
+                bindings::request_threaded_irq(
+                    irq,
+                    Some(primary_cb::<T>),
+                    Some(thread_cb::<T>),
+                    flags,
+                    name,
+                    dev_id,
+                )
+            };
+
+            if ret < 0 {
+                // Leave `dev_id` null so the drop-time `free_irq` is skipped.
+                return Err(Error::from_errno(ret));
+            }
+
+            // SAFETY: Pointer is valid for lifetime of registration.
+            unsafe {
+                (*slot_ptr).dev_id = dev_id;
+            }
+            Ok(())
+        })
+    }
+}
+
 /// Adapter function called from C interrupt handler.
 ///
 /// This function is called by the kernel when an interrupt occurs.
@@ -360,5 +655,47 @@ unsafe extern "C" fn irq_handler_callback<T: IrqHandler>(
     // SAFETY: We're in IRQ context.
     let ctx = unsafe { IrqContext::new() };
 
-    T::handle_irq(&reg.handler_data, &ctx) as _
+    let ret = T::handle_irq(&reg.handler_data, &ctx);
+    reg.account(ret);
+    ret.into_raw()
+}
+
+/// Primary (hard-IRQ) adapter for a threaded registration.
+///
+/// This is the top half that runs in hard IRQ context and decides whether to
+/// wake the handler thread. It reuses the same [`IrqContext`] path as the
+/// non-threaded callback.
+///
+/// # Safety
+///
+/// Same requirements as [`irq_handler_callback`].
+unsafe extern "C" fn primary_cb<T: ThreadedIrqHandler>(
+    irq: core::ffi::c_int,
+    dev_id: *mut core::ffi::c_void,
+) -> core::ffi::c_uint {
+    // SAFETY: `T: ThreadedIrqHandler` implies `T: IrqHandler`, so the
+    // registration layout is identical and `dev_id` stays valid.
+    unsafe { irq_handler_callback::<T>(irq, dev_id) }
+}
+
+/// Thread adapter for a threaded registration.
+///
+/// Runs in the kthread bottom half after the primary returns
+/// [`IrqReturn::WakeThread`].
+///
+/// # Safety
+///
+/// - Must only be called by the kernel's IRQ subsystem
+/// - `dev_id` must be a valid pointer to `IrqRegistration<T>` created during registration
+/// - Must be called in thread context by the kernel's IRQ thread
+unsafe extern "C" fn thread_cb<T: ThreadedIrqHandler>(
+    _irq: core::ffi::c_int,
+    dev_id: *mut core::ffi::c_void,
+) -> core::ffi::c_uint {
+    // SAFETY: dev_id is a valid pointer to IrqRegistration<T> because
+    // we passed it during registration and the kernel passes it back unchanged.
+    let reg = unsafe { &*(dev_id as *const IrqRegistration<T>) };
+
+    let ctx = ThreadContext::new();
+    T::handle_thread(&reg.handler_data, &ctx).into_raw()
 }
@@ -0,0 +1,333 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Interrupt controller (irqchip) and interrupt domain abstractions.
+//!
+//! While the rest of this module lets a driver *consume* an already-numbered
+//! Linux `virq` via [`request_irq`], this submodule lets a driver *implement*
+//! an interrupt controller: a GIC-style or SoC-specific controller that decodes
+//! its own hardware IRQ lines and forwards them into Linux's genirq core.
+//!
+//! An implementor provides an [`IrqChip`] (the per-line `mask`/`unmask`/`ack`/
+//! `eoi` operations) and creates an [`IrqDomain`] that maps hardware IRQ
+//! numbers (`hwirq`) onto Linux `virq`s. A parent handler decodes a pending
+//! `hwirq` and calls [`IrqDomain::generic_handle`] to dispatch it.
+//!
+//! [`request_irq`]: crate::irq::IrqRegistration::try_request
+
+use crate::{
+    bindings,
+    error::{to_result, Error, Result},
+    prelude::*,
+    str::CStr,
+};
+use core::marker::PhantomData;
+
+/// An opaque handle to a `struct irq_data` passed to [`IrqChip`] callbacks.
+///
+/// This identifies the interrupt the callback is acting on. It is only valid
+/// for the duration of the callback.
+pub struct IrqData {
+    ptr: *mut bindings::irq_data,
+}
+
+impl IrqData {
+    /// Builds an `IrqData` from a raw pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point at a valid `struct irq_data` for the duration of the
+    /// returned handle, and the caller must be in a context where operating on
+    /// the interrupt is permitted.
+    unsafe fn from_raw(ptr: *mut bindings::irq_data) -> Self {
+        Self { ptr }
+    }
+
+    /// Returns the hardware IRQ number this interrupt is mapped from.
+    pub fn hwirq(&self) -> u32 {
+        // SAFETY: `ptr` is valid for the lifetime of this handle.
+        unsafe { bindings::irqd_to_hwirq(self.ptr) as u32 }
+    }
+
+    /// Returns the Linux `virq` number for this interrupt.
+    pub fn irq(&self) -> u32 {
+        // SAFETY: `ptr` is valid for the lifetime of this handle.
+        unsafe { (*self.ptr).irq as u32 }
+    }
+
+    /// Returns the raw `struct irq_data` pointer.
+    pub(crate) fn as_raw(&self) -> *mut bindings::irq_data {
+        self.ptr
+    }
+}
+
+/// The trigger type requested for an interrupt line, as passed to
+/// [`IrqChip::set_type`].
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TriggerType {
+    /// Rising edge triggered.
+    EdgeRising = bindings::IRQ_TYPE_EDGE_RISING,
+    /// Falling edge triggered.
+    EdgeFalling = bindings::IRQ_TYPE_EDGE_FALLING,
+    /// High level triggered.
+    LevelHigh = bindings::IRQ_TYPE_LEVEL_HIGH,
+    /// Low level triggered.
+    LevelLow = bindings::IRQ_TYPE_LEVEL_LOW,
+}
+
+/// Operations of an interrupt controller, bridged onto `struct irq_chip`.
+///
+/// Implement this trait to describe how to mask, acknowledge and end
+/// interrupts on a controller written in Rust.
+pub trait IrqChip: Send + Sync + Sized {
+    /// Mask (disable) the interrupt line.
+    fn mask(&self, data: &IrqData);
+
+    /// Unmask (enable) the interrupt line.
+    fn unmask(&self, data: &IrqData);
+
+    /// Acknowledge the interrupt at the controller.
+    fn ack(&self, data: &IrqData);
+
+    /// Signal end-of-interrupt to the controller.
+    ///
+    /// The default implementation does nothing, for controllers that only need
+    /// an explicit `ack`.
+    fn eoi(&self, _data: &IrqData) {}
+
+    /// Configure the trigger type of the line.
+    ///
+    /// The default implementation rejects all types; controllers that support
+    /// reconfiguration should override it.
+    fn set_type(&self, _data: &IrqData, _ty: TriggerType) -> Result {
+        Err(ENOSYS)
+    }
+}
+
+/// An interrupt domain that maps hardware IRQ numbers onto Linux `virq`s.
+///
+/// Created with [`IrqDomain::add_linear`], it owns the backing
+/// `struct irq_domain` and the [`IrqChip`] instance that its mapped
+/// interrupts use.
+#[pin_data(PinnedDrop)]
+pub struct IrqDomain<C: IrqChip> {
+    domain: *mut bindings::irq_domain,
+    #[pin]
+    chip: C,
+    _p: PhantomData<C>,
+}
+
+// SAFETY: The domain and chip are both `Send`/`Sync`; the raw domain pointer is
+// only used under the genirq locks taken by the wrapped C helpers.
+unsafe impl<C: IrqChip> Send for IrqDomain<C> {}
+// SAFETY: See above.
+unsafe impl<C: IrqChip> Sync for IrqDomain<C> {}
+
+impl<C: IrqChip> IrqDomain<C> {
+    /// Create a linear interrupt domain with `size` hardware IRQ slots.
+    ///
+    /// Registers an `irq_domain_ops` whose `map` callback installs `chip` on
+    /// each mapped `virq` and whose `unmap` callback removes it; `xlate` is
+    /// the generic one/two-cell translation.
+    ///
+    /// # Safety
+    ///
+    /// `fwnode` must be a valid firmware node pointer for the controller, or
+    /// null to create an orphan domain.
+    pub unsafe fn add_linear(
+        fwnode: *mut bindings::fwnode_handle,
+        size: u32,
+        chip: C,
+    ) -> Result<Pin<KBox<Self>>> {
+        let domain = KBox::pin_init(
+            try_pin_init!(Self {
+                domain: core::ptr::null_mut(),
+                chip,
+                _p: PhantomData,
+            }),
+            GFP_KERNEL,
+        )?;
+
+        // SAFETY: `chip` is pinned inside the box for the domain's lifetime, so
+        // using a pointer to it as `host_data` is sound.
+        let host_data = &domain.chip as *const C as *mut core::ffi::c_void;
+
+        // SAFETY: `fwnode` is valid per the caller, `OPS` is a valid static
+        // ops table, and `host_data` outlives the domain.
+        //
+        // Use `irq_domain_create_linear`, which takes a `fwnode_handle *`
+        // directly; `irq_domain_add_linear` expects a `struct device_node *` and
+        // reinterpreting the fwnode as one is type confusion.
+        let raw = unsafe {
+            bindings::irq_domain_create_linear(
+                fwnode,
+                size,
+                &OPS::<C>::TABLE as *const _ as *mut _,
+                host_data,
+            )
+        };
+        if raw.is_null() {
+            return Err(ENOMEM);
+        }
+
+        // SAFETY: `domain` is pinned; we only mutate the raw pointer field.
+        unsafe {
+            let this = Pin::get_unchecked_mut(domain.as_mut());
+            this.domain = raw;
+        }
+        Ok(domain)
+    }
+
+    /// Create (or return an existing) mapping for `hwirq`, yielding its `virq`.
+    pub fn create_mapping(&self, hwirq: u32) -> Result<u32> {
+        // SAFETY: `self.domain` is a valid domain for this object's lifetime.
+        let virq = unsafe { bindings::irq_create_mapping(self.domain, hwirq as _) };
+        if virq == 0 {
+            return Err(Error::from_errno(-(bindings::ENOMEM as i32)));
+        }
+        Ok(virq)
+    }
+
+    /// Associate an existing `virq` with `hwirq` in this domain.
+    pub fn associate(&self, virq: u32, hwirq: u32) -> Result {
+        // SAFETY: `self.domain` is valid; `virq`/`hwirq` are plain numbers.
+        to_result(unsafe { bindings::irq_domain_associate(self.domain, virq, hwirq as _) })
+    }
+
+    /// Dispatch a decoded hardware IRQ into the genirq core.
+    ///
+    /// Callers invoke this from their parent (chained) handler once they have
+    /// decoded which `hwirq` fired; it resolves the `virq` and runs its flow
+    /// handler.
+    pub fn generic_handle(&self, hwirq: u32) -> Result {
+        // SAFETY: `self.domain` is valid for this object's lifetime.
+        to_result(unsafe { bindings::generic_handle_domain_irq(self.domain, hwirq as _) })
+    }
+
+    /// Returns the raw `struct irq_domain` pointer.
+    pub(crate) fn as_raw(&self) -> *mut bindings::irq_domain {
+        self.domain
+    }
+}
+
+#[pinned_drop]
+impl<C: IrqChip> PinnedDrop for IrqDomain<C> {
+    fn drop(self: Pin<&mut Self>) {
+        if !self.domain.is_null() {
+            // SAFETY: The domain was created by us and has no live mappings once
+            // the owning object is dropped.
+            unsafe {
+                bindings::irq_domain_remove(self.domain);
+            }
+        }
+    }
+}
+
+/// Holder for the per-chip static `irq_chip` and `irq_domain_ops` tables.
+struct OPS<C: IrqChip>(PhantomData<C>);
+
+impl<C: IrqChip> OPS<C> {
+    const CHIP: bindings::irq_chip = bindings::irq_chip {
+        name: c_str!("rust_irqchip").as_char_ptr(),
+        irq_mask: Some(chip_mask::<C>),
+        irq_unmask: Some(chip_unmask::<C>),
+        irq_ack: Some(chip_ack::<C>),
+        irq_eoi: Some(chip_eoi::<C>),
+        irq_set_type: Some(chip_set_type::<C>),
+        ..unsafe { core::mem::zeroed() }
+    };
+
+    const TABLE: bindings::irq_domain_ops = bindings::irq_domain_ops {
+        map: Some(domain_map::<C>),
+        unmap: Some(domain_unmap::<C>),
+        xlate: Some(bindings::irq_domain_xlate_onetwocell),
+        ..unsafe { core::mem::zeroed() }
+    };
+}
+
+/// Recover the `IrqChip` instance stored as `irq_data`'s chip data.
+///
+/// # Safety
+///
+/// `d` must be a valid `struct irq_data` whose chip data was set to a live
+/// `C` by [`domain_map`].
+unsafe fn chip_of<'a, C: IrqChip>(d: *mut bindings::irq_data) -> &'a C {
+    // SAFETY: chip data was installed as a `*const C` pointing at the pinned
+    // chip owned by the domain, which outlives every interrupt it maps.
+    unsafe { &*(bindings::irq_data_get_irq_chip_data(d) as *const C) }
+}
+
+unsafe extern "C" fn chip_mask<C: IrqChip>(d: *mut bindings::irq_data) {
+    // SAFETY: genirq passes a valid `irq_data`; chip data is a live `C`.
+    let chip = unsafe { chip_of::<C>(d) };
+    // SAFETY: `d` is valid for the duration of this callback.
+    chip.mask(&unsafe { IrqData::from_raw(d) });
+}
+
+unsafe extern "C" fn chip_unmask<C: IrqChip>(d: *mut bindings::irq_data) {
+    // SAFETY: see `chip_mask`.
+    let chip = unsafe { chip_of::<C>(d) };
+    // SAFETY: `d` is valid for the duration of this callback.
+    chip.unmask(&unsafe { IrqData::from_raw(d) });
+}
+
+unsafe extern "C" fn chip_ack<C: IrqChip>(d: *mut bindings::irq_data) {
+    // SAFETY: see `chip_mask`.
+    let chip = unsafe { chip_of::<C>(d) };
+    // SAFETY: `d` is valid for the duration of this callback.
+    chip.ack(&unsafe { IrqData::from_raw(d) });
+}
+
+unsafe extern "C" fn chip_eoi<C: IrqChip>(d: *mut bindings::irq_data) {
+    // SAFETY: see `chip_mask`.
+    let chip = unsafe { chip_of::<C>(d) };
+    // SAFETY: `d` is valid for the duration of this callback.
+    chip.eoi(&unsafe { IrqData::from_raw(d) });
+}
+
+unsafe extern "C" fn chip_set_type<C: IrqChip>(
+    d: *mut bindings::irq_data,
+    flow_type: core::ffi::c_uint,
+) -> core::ffi::c_int {
+    let ty = match flow_type {
+        bindings::IRQ_TYPE_EDGE_RISING => TriggerType::EdgeRising,
+        bindings::IRQ_TYPE_EDGE_FALLING => TriggerType::EdgeFalling,
+        bindings::IRQ_TYPE_LEVEL_HIGH => TriggerType::LevelHigh,
+        bindings::IRQ_TYPE_LEVEL_LOW => TriggerType::LevelLow,
+        _ => return -(bindings::EINVAL as i32),
+    };
+    // SAFETY: see `chip_mask`.
+    let chip = unsafe { chip_of::<C>(d) };
+    // SAFETY: `d` is valid for the duration of this callback.
+    match chip.set_type(&unsafe { IrqData::from_raw(d) }, ty) {
+        Ok(()) => 0,
+        Err(e) => e.to_errno(),
+    }
+}
+
+unsafe extern "C" fn domain_map<C: IrqChip>(
+    domain: *mut bindings::irq_domain,
+    virq: core::ffi::c_uint,
+    _hwirq: bindings::irq_hw_number_t,
+) -> core::ffi::c_int {
+    // SAFETY: genirq passes a valid domain whose `host_data` is the pinned `C`.
+    let host = unsafe { (*domain).host_data };
+    // SAFETY: `virq` is valid, `CHIP` is a valid static, and `host` is the
+    // chip data installed at domain creation.
+    unsafe {
+        bindings::irq_set_chip_and_handler(virq, &OPS::<C>::CHIP, Some(bindings::handle_level_irq));
+        bindings::irq_set_chip_data(virq, host);
+    }
+    0
+}
+
+unsafe extern "C" fn domain_unmap<C: IrqChip>(
+    _domain: *mut bindings::irq_domain,
+    virq: core::ffi::c_uint,
+) {
+    // SAFETY: `virq` is a valid mapped interrupt being torn down.
+    unsafe {
+        bindings::irq_set_chip_and_handler(virq, core::ptr::null(), None);
+        bindings::irq_set_chip_data(virq, core::ptr::null_mut());
+    }
+}
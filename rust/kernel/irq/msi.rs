@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Message-signaled interrupt (MSI/MSI-X) allocation for PCI devices.
+//!
+//! [`IrqRegistration`] only handles legacy line-based IRQs requested by number.
+//! This submodule lets a driver allocate a block of message-signaled vectors
+//! from a PCI device, get a set of Linux `virq`s back, and register a separate
+//! handler per vector.
+//!
+//! Only the PCI path (`pci_alloc_irq_vectors`) is wrapped; platform-MSI
+//! allocation (`platform_msi_domain_alloc_irqs`) is not provided yet.
+//!
+//! The vectors are owned by an RAII [`MsiVectors`] handle that releases them on
+//! drop. Per-vector handlers are registered through [`MsiVectors::request_vector`],
+//! which reuses the same callback trampoline as [`IrqRegistration::try_request`]
+//! and returns an [`MsiVectorRegistration`] borrowed from the owning
+//! [`MsiVectors`], so the borrow checker keeps the vectors alive until every
+//! per-vector handler has been dropped.
+//!
+//! [`IrqRegistration`]: crate::irq::IrqRegistration
+//! [`IrqRegistration::try_request`]: crate::irq::IrqRegistration::try_request
+
+use crate::{
+    bindings,
+    error::{to_result, Result},
+    irq::{IrqFlags, IrqHandler, IrqRegistration},
+    prelude::*,
+    str::CStr,
+};
+use core::marker::PhantomData;
+
+/// Selection of which message-signaled mechanisms a request may fall back to.
+///
+/// Mirrors the kernel's `PCI_IRQ_*` masks passed to `pci_alloc_irq_vectors`.
+#[derive(Debug, Copy, Clone)]
+pub struct MsiFlags(u32);
+
+impl MsiFlags {
+    /// Allow MSI-X vectors.
+    pub const MSIX: Self = Self(bindings::PCI_IRQ_MSIX);
+    /// Allow MSI vectors.
+    pub const MSI: Self = Self(bindings::PCI_IRQ_MSI);
+    /// Allow the legacy (INTx) line as a fallback.
+    pub const LEGACY: Self = Self(bindings::PCI_IRQ_LEGACY);
+    /// Allow any of the supported mechanisms.
+    pub const ALL_TYPES: Self = Self(bindings::PCI_IRQ_ALL_TYPES);
+
+    const fn raw(self) -> u32 {
+        self.0
+    }
+}
+
+impl core::ops::BitOr for MsiFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// An RAII handle to a block of message-signaled vectors on a PCI device.
+///
+/// Frees the vectors with `pci_free_irq_vectors` on drop.
+pub struct MsiVectors<T: IrqHandler> {
+    pdev: *mut bindings::pci_dev,
+    count: u32,
+    _p: PhantomData<T>,
+}
+
+// SAFETY: The vectors are owned exclusively by this handle, and the genirq
+// helpers it calls take their own locks.
+unsafe impl<T: IrqHandler> Send for MsiVectors<T> {}
+// SAFETY: See above.
+unsafe impl<T: IrqHandler> Sync for MsiVectors<T> {}
+
+impl<T: IrqHandler> MsiVectors<T> {
+    /// Allocate between `min` and `max` message-signaled vectors from `pdev`.
+    ///
+    /// `flags` selects between MSI-X, MSI and legacy fallback like the kernel's
+    /// `PCI_IRQ_*` masks. On success the handle owns `count()` vectors.
+    ///
+    /// # Safety
+    ///
+    /// `pdev` must point at a valid, enabled `struct pci_dev` that outlives the
+    /// returned handle.
+    pub unsafe fn alloc_pci(
+        pdev: *mut bindings::pci_dev,
+        min: u32,
+        max: u32,
+        flags: MsiFlags,
+    ) -> Result<Self> {
+        // SAFETY: `pdev` is valid per the caller.
+        let ret = unsafe {
+            bindings::pci_alloc_irq_vectors(pdev, min, max, flags.raw() as _)
+        };
+        to_result(ret)?;
+        Ok(Self {
+            pdev,
+            count: ret as u32,
+            _p: PhantomData,
+        })
+    }
+
+    /// Number of vectors actually allocated.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Returns the Linux `virq` for vector index `i`.
+    ///
+    /// Returns `None` if `i` is out of range.
+    pub fn virq(&self, i: u32) -> Option<u32> {
+        if i >= self.count {
+            return None;
+        }
+        // SAFETY: `pdev` is valid and `i` is within the allocated range.
+        let virq = unsafe { bindings::pci_irq_vector(self.pdev, i) };
+        (virq >= 0).then_some(virq as u32)
+    }
+
+    /// Register `handler_data` on the `virq` backing vector index `i`.
+    ///
+    /// Built on the same callback trampoline as
+    /// [`IrqRegistration::try_request`]. The returned [`MsiVectorRegistration`]
+    /// borrows `self`, so the vectors cannot be freed while a per-vector handler
+    /// is still registered on them.
+    pub fn request_vector(
+        &self,
+        i: u32,
+        handler_data: T::Data,
+        flags: IrqFlags,
+        name: &CStr,
+    ) -> Result<MsiVectorRegistration<'_, T>> {
+        let virq = self.virq(i).ok_or(EINVAL)?;
+        // SAFETY: `virq` is a valid IRQ number returned by the MSI allocation.
+        let reg = KBox::pin_init(
+            unsafe { IrqRegistration::<T>::try_request(virq, handler_data, flags, name) },
+            GFP_KERNEL,
+        )?;
+        Ok(MsiVectorRegistration {
+            reg,
+            _p: PhantomData,
+        })
+    }
+}
+
+/// A per-vector handler registration tied to its owning [`MsiVectors`].
+///
+/// Derefs to the underlying [`IrqRegistration`]. Because it borrows the
+/// [`MsiVectors`] it was requested from, the vectors stay allocated for at least
+/// as long as this registration lives, closing the use-after-free window that
+/// an independently-owned registration would open.
+pub struct MsiVectorRegistration<'a, T: IrqHandler> {
+    reg: Pin<KBox<IrqRegistration<T>>>,
+    _p: PhantomData<&'a MsiVectors<T>>,
+}
+
+impl<T: IrqHandler> core::ops::Deref for MsiVectorRegistration<'_, T> {
+    type Target = IrqRegistration<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.reg
+    }
+}
+
+impl<T: IrqHandler> Drop for MsiVectors<T> {
+    fn drop(&mut self) {
+        // SAFETY: `pdev` is valid and the vectors were allocated by us; all
+        // per-vector registrations must have been dropped first.
+        unsafe {
+            bindings::pci_free_irq_vectors(self.pdev);
+        }
+    }
+}
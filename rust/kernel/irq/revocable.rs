@@ -6,7 +6,7 @@
 //! useful for scenarios where handlers need to be dynamically disabled.
 
 use crate::{
-    irq::{IrqContext, IrqHandler, IrqReturn, ThreadContext, ThreadedIrqHandler},
+    irq::{IrqContext, IrqHandler, IrqRegistration, IrqReturn, ThreadContext, ThreadedIrqHandler},
     revocable::{Revocable, RevocableGuard},
     sync::Arc,
 };
@@ -26,10 +26,32 @@ impl<T: Send + Sync> RevocableIrqData<T> {
     }
 
     /// Revoke access to the data.
+    ///
+    /// This is non-blocking and safe to call from any context (including
+    /// hard-IRQ), but it does not wait for a handler that is already running:
+    /// a hard IRQ may be executing `handle_irq` on another CPU at the moment of
+    /// revoke, so `try_access()` returning `None` afterwards does not guarantee
+    /// the previous access has finished. Use [`revoke_sync`] when that
+    /// guarantee is required.
+    ///
+    /// [`revoke_sync`]: Self::revoke_sync
     pub fn revoke(&self) {
         self.inner.revoke();
     }
 
+    /// Revoke access and wait for any in-flight handler to finish.
+    ///
+    /// After revoking, this blocks (via [`IrqRegistration::synchronize`]) until
+    /// no handler is running on `reg`'s line, closing the use-after-revoke
+    /// window left by [`revoke`]. Because it may sleep, it must only be called
+    /// from a sleepable context, never from hard-IRQ context.
+    ///
+    /// [`revoke`]: Self::revoke
+    pub fn revoke_sync<H: IrqHandler>(&self, reg: &IrqRegistration<H>) {
+        self.inner.revoke();
+        reg.synchronize();
+    }
+
     /// Try to access the inner data.
     pub fn try_access(&self) -> Option<RevocableGuard<'_, T>> {
         self.inner.try_access()